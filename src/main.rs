@@ -2,26 +2,35 @@ use std::{
     collections::HashMap,
     fmt::Debug,
     fs::{self, File},
-    io::{BufRead, BufReader, Read},
+    io::{BufRead, BufReader, Read, Write},
     path::{Path, PathBuf},
     str,
+    time::{Duration, Instant, SystemTime},
 };
 
 use anyhow::Context;
 use base64::{engine::general_purpose, Engine};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    XChaCha20Poly1305, XNonce,
+};
 use clap::Parser;
+use futures_util::{SinkExt, StreamExt};
+use sha2::{Digest, Sha256};
 use env_logger::{Builder, Target};
 use log::{debug, error, info};
+use regex::Regex;
 use serde_derive::Deserialize;
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
-    net::{TcpListener, TcpStream, ToSocketAddrs},
+    net::{tcp::OwnedWriteHalf, TcpListener, TcpStream, ToSocketAddrs},
     select,
     sync::{
         broadcast::{self, Receiver},
         mpsc::{self, UnboundedReceiver, UnboundedSender},
     },
 };
+use tokio_tungstenite::tungstenite::Message;
 
 mod emu;
 mod option_future;
@@ -51,6 +60,9 @@ struct Config {
     #[serde(default)]
     storage: HashMap<String, FileContents>,
     startup: Option<String>,
+    ws_bind: Option<String>,
+    flash_persist: Option<String>,
+    psk: Option<String>,
 }
 
 impl Config {
@@ -121,9 +133,206 @@ struct Args {
     #[arg(short = 'o')]
     log_file: Option<PathBuf>,
 
+    #[arg(short = 'w', long = "ws-bind")]
+    ws_bind: Option<String>,
+
+    #[arg(long = "record")]
+    record: Option<PathBuf>,
+
+    #[arg(long = "replay")]
+    replay: Option<PathBuf>,
+
+    #[arg(long = "flash-persist")]
+    flash_persist: Option<String>,
+
+    /// Interval, in seconds, between periodic flash snapshots.
+    #[arg(long = "flash-persist-interval", default_value_t = 60)]
+    flash_persist_interval: u64,
+
+    /// Hex-encoded pre-shared key enabling XChaCha20-Poly1305 framing on the
+    /// TCP transport.
+    #[arg(long = "psk")]
+    psk: Option<String>,
+
+    /// Headless mode: evaluate the given JS script, stream its console output
+    /// to stdout, and exit according to a pass/fail sentinel.
+    #[arg(long = "run")]
+    run: Option<PathBuf>,
+
+    /// Regex whose first capture group (`PASS`/`FAIL`) terminates a headless
+    /// run.
+    #[arg(long = "run-sentinel", default_value = r"BANGLE_TEST_RESULT:(PASS|FAIL)")]
+    run_sentinel: String,
+
+    /// Optional timeout, in seconds, after which a headless run fails.
+    #[arg(long = "run-timeout")]
+    run_timeout: Option<u64>,
+
     wasm_path: PathBuf,
 }
 
+/// Parses a hex string into its raw bytes.
+fn parse_hex(s: &str) -> anyhow::Result<Vec<u8>> {
+    let s = s.trim();
+    anyhow::ensure!(s.len() % 2 == 0, "odd-length hex string");
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(Into::into))
+        .collect()
+}
+
+/// Builds an XChaCha20-Poly1305 cipher from a hex-encoded pre-shared key by
+/// hashing the secret down to a 32-byte key.
+fn build_cipher(psk: &str) -> anyhow::Result<XChaCha20Poly1305> {
+    let secret = parse_hex(psk).context("invalid --psk hex")?;
+    let key = Sha256::digest(&secret);
+    Ok(XChaCha20Poly1305::new(&key))
+}
+
+/// Encrypts one plaintext message into a `nonce (24B) || ciphertext || tag
+/// (16B)` frame with a fresh random nonce.
+fn encrypt_frame(cipher: &XChaCha20Poly1305, plaintext: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .map_err(|e| anyhow::anyhow!("encrypt failed: {e}"))?;
+    let mut frame = Vec::with_capacity(nonce.len() + ciphertext.len());
+    frame.extend_from_slice(nonce.as_slice());
+    frame.extend_from_slice(&ciphertext);
+    Ok(frame)
+}
+
+/// Upper bound on an encrypted frame's declared length. Console frames are
+/// tiny; anything larger is a malformed or hostile peer and is rejected before
+/// allocating, so a bad length prefix can't drive a multi-gigabyte allocation.
+const MAX_FRAME_LEN: usize = 64 * 1024;
+
+/// Remembers the most recently seen nonces so replays can be rejected without
+/// the set growing unbounded over a long-lived connection: once the window is
+/// full the oldest nonce is evicted.
+struct NonceWindow {
+    order: std::collections::VecDeque<[u8; 24]>,
+    seen: std::collections::HashSet<[u8; 24]>,
+}
+
+impl NonceWindow {
+    /// Number of recent nonces retained per connection.
+    const CAPACITY: usize = 4096;
+
+    fn new() -> Self {
+        Self {
+            order: std::collections::VecDeque::with_capacity(Self::CAPACITY),
+            seen: std::collections::HashSet::with_capacity(Self::CAPACITY),
+        }
+    }
+
+    /// Records `nonce`, returning `false` if it was seen within the window.
+    fn insert(&mut self, nonce: [u8; 24]) -> bool {
+        if !self.seen.insert(nonce) {
+            return false;
+        }
+        self.order.push_back(nonce);
+        if self.order.len() > Self::CAPACITY {
+            if let Some(old) = self.order.pop_front() {
+                self.seen.remove(&old);
+            }
+        }
+        true
+    }
+}
+
+/// Decrypts one `nonce || ciphertext || tag` frame, rejecting reused nonces and
+/// tag-verification failures.
+fn decrypt_frame(
+    cipher: &XChaCha20Poly1305,
+    seen_nonces: &mut NonceWindow,
+    frame: &[u8],
+) -> anyhow::Result<Vec<u8>> {
+    anyhow::ensure!(frame.len() >= 24 + 16, "short frame");
+    let (nonce, ciphertext) = frame.split_at(24);
+    let nonce: [u8; 24] = nonce.try_into().unwrap();
+    anyhow::ensure!(seen_nonces.insert(nonce), "nonce reuse");
+    cipher
+        .decrypt(XNonce::from_slice(&nonce), ciphertext)
+        .map_err(|e| anyhow::anyhow!("tag verification failed: {e}"))
+}
+
+/// Records the bidirectional console stream to an [asciicast v2] file: a JSON
+/// header line followed by one `[elapsed_seconds, "o"|"i", chunk]` array per
+/// event.
+///
+/// [asciicast v2]: https://docs.asciinema.org/manual/asciicast/v2/
+struct AsciicastRecorder {
+    file: File,
+    start: Instant,
+}
+
+impl AsciicastRecorder {
+    fn create<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let mut file = File::create(&path)
+            .with_context(|| format!("Failed to create recording {:?}", path.as_ref()))?;
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        writeln!(
+            file,
+            "{{\"version\":2,\"width\":80,\"height\":24,\"timestamp\":{timestamp}}}"
+        )?;
+        Ok(Self {
+            file,
+            start: Instant::now(),
+        })
+    }
+
+    /// Appends a single event. `kind` is `"o"` for emulator output and `"i"`
+    /// for console input.
+    fn record(&mut self, kind: &str, data: &[u8]) {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let chunk = String::from_utf8_lossy(data);
+        let event = serde_json::json!([elapsed, kind, chunk]);
+        if let Err(err) = writeln!(self.file, "{event}") {
+            error!("failed to write recording: {err}");
+        }
+    }
+}
+
+/// Reads an asciicast v2 file and replays its recorded input events back into
+/// the emulator, honoring the inter-event delays so a session can be
+/// deterministically reproduced.
+async fn run_replay<P: AsRef<Path>>(
+    path: P,
+    tx: UnboundedSender<Input>,
+) -> anyhow::Result<()> {
+    let f = File::open(&path)
+        .with_context(|| format!("Failed to open replay {:?}", path.as_ref()))?;
+    let mut lines = BufReader::new(f).lines();
+    // The first line is the header; input events follow.
+    lines.next();
+
+    let start = Instant::now();
+    for line in lines {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event: serde_json::Value = serde_json::from_str(&line)?;
+        if event[1].as_str() != Some("i") {
+            continue;
+        }
+        let elapsed = Duration::from_secs_f64(event[0].as_f64().unwrap_or(0.0));
+        if let Some(delay) = elapsed.checked_sub(start.elapsed()) {
+            tokio::time::sleep(delay).await;
+        }
+        let chunk = event[2].as_str().unwrap_or("").as_bytes().to_owned();
+        if tx.send(Input::Console(chunk)).is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
 fn get_flash_initial_contents<P: AsRef<Path>>(path: P) -> anyhow::Result<Vec<u8>> {
     let f = File::open(path)?;
     let f = BufReader::new(f);
@@ -145,52 +354,307 @@ fn get_flash_initial_contents<P: AsRef<Path>>(path: P) -> anyhow::Result<Vec<u8>
     Ok(ret)
 }
 
+/// Serializes a full flash image in the same decimal-CSV byte format parsed by
+/// [`get_flash_initial_contents`], so persisted images round-trip as
+/// `flash_initial_contents_file` inputs.
+fn write_flash_contents<P: AsRef<Path>>(path: P, flash: &[u8]) -> anyhow::Result<()> {
+    let row = flash
+        .iter()
+        .map(|b| b.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    fs::write(&path, row)
+        .with_context(|| format!("Failed to persist flash to {:?}", path.as_ref()))?;
+    Ok(())
+}
+
+/// Control bytes delimiting a flash dump in the console stream. `Flash.read`
+/// returns a `Uint8Array` whose string form is already the decimal-CSV byte
+/// format we persist, so the payload between the markers round-trips directly.
+const FLASH_DUMP_START: &[u8] = b"\x01FLASH:";
+const FLASH_DUMP_END: u8 = 0x04;
+
+/// Console command that prints the *complete* emulated flash image wrapped in
+/// the dump markers. Walks every flash page in address order (rather than the
+/// free regions `getFree()` reports) so Storage writes made during the session
+/// are captured and the output round-trips as a full `flash_initial_contents_file`
+/// against `new_with_flash`. Sent through the same `\x10`-prefixed paste
+/// mechanism the config uses to seed `Storage`, so no emulator-side API is
+/// required.
+///
+/// The marker bytes are produced at runtime with `String.fromCharCode` so the
+/// command source never contains the raw marker bytes themselves; this way the
+/// collector can't false-trigger if the emulator echoes the pasted command.
+fn flash_dump_command() -> Vec<u8> {
+    concat!(
+        "\x10{var f=require('Flash'),o=[],a=0,p;",
+        "try{while(p=f.getPage(a)){o.push(f.read(p.length,p.addr).join(','));a=p.addr+p.length;}}catch(e){}",
+        "print(String.fromCharCode(1)+'FLASH:'+o.join(',')+String.fromCharCode(4));}\n"
+    )
+    .as_bytes()
+    .to_vec()
+}
+
+/// Extracts delimited flash dumps from the console output stream while letting
+/// all other bytes flow through untouched, so persistence can reuse the one
+/// console channel the emulator exposes without corrupting interactive output.
+#[derive(Default)]
+struct FlashDumpCollector {
+    buf: Vec<u8>,
+    capturing: bool,
+}
+
+impl FlashDumpCollector {
+    /// Feeds one console chunk, returning the bytes to forward to consumers and
+    /// any complete dump payloads found.
+    fn push(&mut self, chunk: &[u8]) -> (Vec<u8>, Vec<Vec<u8>>) {
+        self.buf.extend_from_slice(chunk);
+        let mut passthrough = Vec::new();
+        let mut payloads = Vec::new();
+
+        loop {
+            if self.capturing {
+                match self.buf.iter().position(|&b| b == FLASH_DUMP_END) {
+                    Some(pos) => {
+                        payloads.push(self.buf[..pos].to_vec());
+                        self.buf.drain(..=pos);
+                        self.capturing = false;
+                    }
+                    None => break,
+                }
+            } else {
+                match find_subslice(&self.buf, FLASH_DUMP_START) {
+                    Some(pos) => {
+                        passthrough.extend_from_slice(&self.buf[..pos]);
+                        self.buf.drain(..pos + FLASH_DUMP_START.len());
+                        self.capturing = true;
+                    }
+                    None => {
+                        // Retain a short tail that might be a split start marker.
+                        let keep = FLASH_DUMP_START.len() - 1;
+                        let emit = self.buf.len().saturating_sub(keep);
+                        passthrough.extend_from_slice(&self.buf[..emit]);
+                        self.buf.drain(..emit);
+                        break;
+                    }
+                }
+            }
+        }
+
+        (passthrough, payloads)
+    }
+}
+
+/// Finds the first occurrence of `needle` in `haystack`.
+fn find_subslice(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|w| w == needle)
+}
+
+/// Parses a decimal-CSV flash dump payload into raw bytes.
+fn parse_flash_dump(payload: &[u8]) -> anyhow::Result<Vec<u8>> {
+    str::from_utf8(payload)?
+        .split(',')
+        .filter(|f| !f.is_empty())
+        .map(|f| f.trim().parse::<u8>().map_err(Into::into))
+        .collect()
+}
+
+/// Parses a flash dump payload and persists it to `path`, logging any failure.
+fn persist_flash_dump(path: Option<&str>, payload: &[u8]) {
+    let Some(path) = path else { return };
+    match parse_flash_dump(payload) {
+        Ok(flash) => {
+            if let Err(err) = write_flash_contents(path, &flash) {
+                error!("{err:#}");
+            }
+        }
+        Err(err) => error!("failed to parse flash dump: {err}"),
+    }
+}
+
 async fn run_net(
     bind: impl ToSocketAddrs + Debug,
     mut rx: UnboundedReceiver<Vec<u8>>,
     tx: UnboundedSender<Input>,
+    cipher: Option<XChaCha20Poly1305>,
     mut quit: Receiver<()>,
 ) -> anyhow::Result<()> {
     let listener = TcpListener::bind(&bind)
         .await
         .with_context(|| format!("Failed to bind {bind:?}"))?;
-    let mut socket: Option<TcpStream> = None;
+    // Each connected client gets its own buffered writer task fed by a private
+    // channel, so a single slow or back-pressured socket can't stall console
+    // delivery to the others or block new connections. Outbound data is handed
+    // to each client's channel; clients whose task has exited are dropped.
+    let mut clients: Vec<UnboundedSender<Vec<u8>>> = vec![];
+
+    loop {
+        select! {
+            _ = quit.recv() => break,
+            new_conn = listener.accept() => {
+                let (s, addr) = new_conn?;
+                info!("got connection from {addr}");
+                let (read, write) = s.into_split();
+
+                let (client_tx, client_rx) = mpsc::unbounded_channel();
+                clients.push(client_tx);
+                let write_cipher = cipher.clone();
+                let writer = tokio::spawn(async move {
+                    if let Err(err) = run_client_writer(write, client_rx, write_cipher).await {
+                        debug!("client writer for {addr} stopped: {err}");
+                    }
+                });
+
+                let tx = tx.clone();
+                let read_cipher = cipher.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = forward_reads(read, tx, read_cipher).await {
+                        error!("socket err from {addr}: {err}");
+                    }
+                    debug!("connection from {addr} closed");
+                    // Dropping the read half alone leaves the socket open; stop
+                    // the writer so the write half is dropped and it closes.
+                    writer.abort();
+                });
+            }
+            data = rx.recv() => {
+                let data = data.unwrap();
+                clients.retain(|client| client.send(data.clone()).is_ok());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Drains a single client's outbound channel, writing each console message to
+/// its socket (length-delimited and encrypted when a cipher is configured, raw
+/// otherwise). Runs independently per client so one stalled socket can't hold
+/// up the others.
+async fn run_client_writer(
+    mut socket: OwnedWriteHalf,
+    mut rx: UnboundedReceiver<Vec<u8>>,
+    cipher: Option<XChaCha20Poly1305>,
+) -> anyhow::Result<()> {
+    while let Some(data) = rx.recv().await {
+        match &cipher {
+            Some(cipher) => {
+                let frame = encrypt_frame(cipher, &data)?;
+                socket.write_all(&(frame.len() as u32).to_be_bytes()).await?;
+                socket.write_all(&frame).await?;
+            }
+            None => socket.write_all(&data).await?,
+        }
+    }
+    Ok(())
+}
+
+/// Drains a client's read half into the shared input channel, decrypting each
+/// length-delimited frame when a cipher is configured. Any framing or
+/// authentication failure drops the connection.
+async fn forward_reads(
+    mut read: tokio::net::tcp::OwnedReadHalf,
+    tx: UnboundedSender<Input>,
+    cipher: Option<XChaCha20Poly1305>,
+) -> anyhow::Result<()> {
+    let mut seen_nonces = NonceWindow::new();
     let mut buf = vec![0u8; 4096];
+    loop {
+        match &cipher {
+            Some(cipher) => {
+                let mut len = [0u8; 4];
+                match read.read_exact(&mut len).await {
+                    Ok(_) => {}
+                    Err(_) => break,
+                }
+                let len = u32::from_be_bytes(len) as usize;
+                anyhow::ensure!(len <= MAX_FRAME_LEN, "frame length {len} exceeds cap");
+                let mut frame = vec![0u8; len];
+                read.read_exact(&mut frame).await?;
+                let plaintext = decrypt_frame(cipher, &mut seen_nonces, &frame)?;
+                if tx.send(Input::Console(plaintext)).is_err() {
+                    break;
+                }
+            }
+            None => match read.read(&mut buf).await? {
+                0 => break,
+                n => {
+                    if tx.send(Input::Console(buf[..n].to_owned())).is_err() {
+                        break;
+                    }
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+async fn run_ws(
+    bind: impl ToSocketAddrs + Debug,
+    mut rx: UnboundedReceiver<Vec<u8>>,
+    tx: UnboundedSender<Input>,
+    mut quit: Receiver<()>,
+) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(&bind)
+        .await
+        .with_context(|| format!("Failed to bind {bind:?}"))?;
+    let mut socket: Option<tokio_tungstenite::WebSocketStream<TcpStream>> = None;
+    // Handshakes run in their own tasks and hand the finished stream back here,
+    // so a slow or failed handshake from a hostile browser client never blocks
+    // output to an existing client and never tears down the task.
+    let (ready_tx, mut ready_rx) = mpsc::unbounded_channel();
 
     loop {
-        let sock_read: option_future::OptionFuture<_> =
-            socket.as_mut().map(|s| s.read(&mut buf)).into();
+        let ws_read: option_future::OptionFuture<_> = socket.as_mut().map(|s| s.next()).into();
         select! {
             _ = quit.recv() => break,
             new_conn = listener.accept() => {
                 let (s, addr) = new_conn?;
+                let ready_tx = ready_tx.clone();
+                tokio::spawn(async move {
+                    match tokio_tungstenite::accept_async(s).await {
+                        Ok(ws) => {
+                            let _ = ready_tx.send((ws, addr));
+                        }
+                        Err(err) => error!("ws handshake from {addr} failed: {err}"),
+                    }
+                });
+            }
+            ready = ready_rx.recv() => {
+                let (ws, addr) = ready.unwrap();
                 match socket {
                     Some(_) => {
-                        debug!("ignoring connection from {addr}");
+                        debug!("ignoring ws connection from {addr}");
                     }
                     None => {
-                        info!("got connection from {addr}");
-                        socket = Some(s);
+                        info!("got ws connection from {addr}");
+                        socket = Some(ws);
                     }
                 }
             }
             data = rx.recv() => {
                 if let Some(socket) = &mut socket {
-                    let _ = socket.write_all(&data.unwrap()).await;
+                    let _ = socket.send(Message::Binary(data.unwrap())).await;
                 }
             }
-            r = sock_read => {
-                debug!("sock read: {r:?}");
-                match r {
-                    Ok(0) => {
-                        debug!("socket connection closed");
-                        socket = None;
+            msg = ws_read => {
+                debug!("ws read: {msg:?}");
+                match msg {
+                    Some(Ok(Message::Binary(b))) => {
+                        tx.send(Input::Console(b)).unwrap();
                     }
-                    Ok(n) => {
-                        tx.send(Input::Console(buf[..n].to_owned())).unwrap();
+                    Some(Ok(Message::Text(t))) => {
+                        tx.send(Input::Console(t.into_bytes())).unwrap();
                     }
-                    Err(err) => {
-                        error!("socket err: {err}");
+                    Some(Ok(_)) => {}
+                    Some(Err(err)) => {
+                        error!("ws err: {err}");
+                        socket = None;
+                    }
+                    None => {
+                        debug!("ws connection closed");
                         socket = None;
                     }
                 }
@@ -214,6 +678,77 @@ async fn run_emu(
     }
 }
 
+/// Runs a single JS script to completion without the TUI, streaming console
+/// output to stdout and returning a process exit code determined by `sentinel`
+/// (0 = pass, 1 = fail / closed, 2 = timeout).
+async fn run_headless(
+    emu: Emulator,
+    script: PathBuf,
+    sentinel: Regex,
+    timeout: Option<Duration>,
+    quit_tx: broadcast::Sender<()>,
+) -> anyhow::Result<i32> {
+    let (to_emu_tx, to_emu_rx) = mpsc::unbounded_channel();
+    let (from_emu_tx, mut from_emu_rx) = mpsc::unbounded_channel();
+    let emu_handle = tokio::spawn(run_emu(emu, to_emu_rx, from_emu_tx, quit_tx.subscribe()));
+
+    let source = fs::read(&script)
+        .with_context(|| format!("Failed to read script {script:?}"))?;
+    // Evaluate the whole script atomically through the same `\x10` paste + atob
+    // mechanism the config uses to seed state, so multi-line functions/blocks
+    // aren't chopped up and evaluated line-by-line by the REPL.
+    let eval = format!(
+        "\x10eval(atob('{}'))\n",
+        general_purpose::STANDARD_NO_PAD.encode(&source)
+    );
+    to_emu_tx.send(Input::Console(eval.into_bytes())).unwrap();
+
+    // Size of the trailing window scanned for the sentinel. Only the tail is
+    // retained so memory stays bounded and each scan is O(window) rather than
+    // O(total output) for a long-running test.
+    const TAIL_CAP: usize = 8 * 1024;
+
+    let deadline = timeout.map(|t| tokio::time::Instant::now() + t);
+    let mut stdout = tokio::io::stdout();
+    let mut tail = String::new();
+
+    let exit_code = loop {
+        let timer: option_future::OptionFuture<_> = deadline.map(tokio::time::sleep_until).into();
+        select! {
+            output = from_emu_rx.recv() => {
+                match output {
+                    Some(Output::Console(data)) => {
+                        let _ = stdout.write_all(&data).await;
+                        let _ = stdout.flush().await;
+                        tail.push_str(&String::from_utf8_lossy(&data));
+                        if tail.len() > TAIL_CAP {
+                            let mut cut = tail.len() - TAIL_CAP;
+                            while !tail.is_char_boundary(cut) {
+                                cut += 1;
+                            }
+                            tail.drain(..cut);
+                        }
+                        if let Some(caps) = sentinel.captures(&tail) {
+                            let pass = caps.get(1).map(|m| m.as_str()) == Some("PASS");
+                            break if pass { 0 } else { 1 };
+                        }
+                    }
+                    Some(_) => {}
+                    None => break 1,
+                }
+            }
+            _ = timer => {
+                error!("headless run timed out after {timeout:?}");
+                break 2;
+            }
+        }
+    };
+
+    drop(quit_tx);
+    let _ = emu_handle.await;
+    Ok(exit_code)
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
@@ -231,12 +766,22 @@ async fn main() -> anyhow::Result<()> {
     }
 
     // Initialize emulator from arguments.
-    let emu = match &args.config_path {
+    let config = match &args.config_path {
         Some(path) => Config::read(path)
             .with_context(|| format!("Failed to open config file {:?}", args.config_path))?,
         None => Config::default(),
+    };
+    let emu = config.build(&args.wasm_path)?;
+
+    // Headless mode short-circuits the interactive TUI/network tasks and turns
+    // the emulator into a CI-friendly test harness.
+    if let Some(script) = args.run {
+        let sentinel = Regex::new(&args.run_sentinel).context("invalid --run-sentinel regex")?;
+        let timeout = args.run_timeout.map(Duration::from_secs);
+        let (quit_tx, _) = broadcast::channel(1);
+        let code = run_headless(emu, script, sentinel, timeout, quit_tx).await?;
+        std::process::exit(code);
     }
-    .build(&args.wasm_path)?;
 
     // Set up independent tasks and channels between them.
     let (to_emu_tx, to_emu_rx) = mpsc::unbounded_channel();
@@ -245,15 +790,53 @@ async fn main() -> anyhow::Result<()> {
     let (from_ui_tx, mut from_ui_rx) = mpsc::unbounded_channel();
     let (to_net_tx, to_net_rx) = mpsc::unbounded_channel();
     let (from_net_tx, mut from_net_rx) = mpsc::unbounded_channel();
+    let (to_ws_tx, to_ws_rx) = mpsc::unbounded_channel();
 
     let (quit_tx, _) = broadcast::channel(1);
 
     let bind = args.bind.as_deref().unwrap_or("127.0.0.1:37026").to_owned();
 
+    let cipher = match args.psk.or(config.psk) {
+        Some(psk) => Some(build_cipher(&psk)?),
+        None => None,
+    };
+
     let emu_handle = tokio::spawn(run_emu(emu, to_emu_rx, from_emu_tx, quit_tx.subscribe()));
-    let net_handle = tokio::spawn(run_net(bind, to_net_rx, from_net_tx, quit_tx.subscribe()));
+    let net_handle = tokio::spawn(run_net(
+        bind,
+        to_net_rx,
+        from_net_tx.clone(),
+        cipher,
+        quit_tx.subscribe(),
+    ));
     let ui_handle = tokio::spawn(ui::run_tui(to_ui_rx, from_ui_tx, quit_tx.subscribe()));
 
+    // Optionally expose the same console byte stream over WebSocket so that
+    // browser-based tooling can attach without a raw-TCP shim.
+    let ws_handle = args
+        .ws_bind
+        .or(config.ws_bind)
+        .map(|ws_bind| tokio::spawn(run_ws(ws_bind, to_ws_rx, from_net_tx, quit_tx.subscribe())));
+
+    // Optionally capture the console stream to an asciicast recording and/or
+    // replay recorded input events back into the emulator.
+    let mut recorder = match args.record {
+        Some(path) => Some(AsciicastRecorder::create(path)?),
+        None => None,
+    };
+    if let Some(path) = args.replay {
+        tokio::spawn(run_replay(path, to_emu_tx.clone()));
+    }
+
+    // Optionally persist the emulator's flash image back to disk on a periodic
+    // interval and on clean shutdown, so Storage writes survive a restart. The
+    // dump is requested over the console and intercepted out of the stream so
+    // interactive output is unaffected.
+    let flash_persist = args.flash_persist.or(config.flash_persist);
+    let mut flash_collector = FlashDumpCollector::default();
+    let mut flash_tick =
+        tokio::time::interval(Duration::from_secs(args.flash_persist_interval.max(1)));
+
     // Run main loop.
     loop {
         select! {
@@ -261,17 +844,77 @@ async fn main() -> anyhow::Result<()> {
                 let output = output.unwrap();
                 if let Output::Console(data) = &output {
                     info!("output: {:?}", str::from_utf8(data));
-                    let _ = to_net_tx.send(data.to_owned());
+                    // Intercept any flash dump before it reaches consumers.
+                    let data = if flash_persist.is_some() {
+                        let (passthrough, payloads) = flash_collector.push(data);
+                        for payload in &payloads {
+                            persist_flash_dump(flash_persist.as_deref(), payload);
+                        }
+                        passthrough
+                    } else {
+                        data.to_owned()
+                    };
+                    if data.is_empty() {
+                        continue;
+                    }
+                    if let Some(recorder) = &mut recorder {
+                        recorder.record("o", &data);
+                    }
+                    let _ = to_net_tx.send(data.clone());
+                    let _ = to_ws_tx.send(data.clone());
+                    let _ = to_ui_tx.send(Output::Console(data));
+                } else {
+                    let _ = to_ui_tx.send(output);
                 }
-                let _ = to_ui_tx.send(output);
+            }
+            _ = flash_tick.tick(), if flash_persist.is_some() => {
+                to_emu_tx.send(Input::Console(flash_dump_command())).unwrap();
             }
             data = from_net_rx.recv() => {
-                to_emu_tx.send(data.unwrap()).unwrap();
+                let data = data.unwrap();
+                if let (Some(recorder), Input::Console(bytes)) = (&mut recorder, &data) {
+                    recorder.record("i", bytes);
+                }
+                to_emu_tx.send(data).unwrap();
             }
             input = from_ui_rx.recv() => {
                 match input.unwrap() {
                     UIInput::Quit => break,
-                    UIInput::EmuInput(input) => to_emu_tx.send(input).unwrap(),
+                    UIInput::EmuInput(input) => {
+                        if let (Some(recorder), Input::Console(bytes)) = (&mut recorder, &input) {
+                            recorder.record("i", bytes);
+                        }
+                        to_emu_tx.send(input).unwrap();
+                    }
+                }
+            }
+        }
+    }
+
+    // Flush a final flash snapshot on clean shutdown before tearing down tasks,
+    // draining console output until the dump arrives or a short timeout elapses.
+    if flash_persist.is_some() {
+        to_emu_tx.send(Input::Console(flash_dump_command())).unwrap();
+        let deadline = tokio::time::sleep(Duration::from_secs(5));
+        tokio::pin!(deadline);
+        loop {
+            select! {
+                _ = &mut deadline => {
+                    error!("timed out waiting for final flash dump");
+                    break;
+                }
+                output = from_emu_rx.recv() => {
+                    match output {
+                        Some(Output::Console(data)) => {
+                            let (_, payloads) = flash_collector.push(&data);
+                            if let Some(payload) = payloads.first() {
+                                persist_flash_dump(flash_persist.as_deref(), payload);
+                                break;
+                            }
+                        }
+                        Some(_) => {}
+                        None => break,
+                    }
                 }
             }
         }
@@ -282,6 +925,9 @@ async fn main() -> anyhow::Result<()> {
     emu_handle.await.unwrap().unwrap();
     net_handle.await.unwrap().unwrap();
     ui_handle.await.unwrap().unwrap();
+    if let Some(ws_handle) = ws_handle {
+        ws_handle.await.unwrap().unwrap();
+    }
 
     Ok(())
 }